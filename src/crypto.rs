@@ -0,0 +1,267 @@
+//! Authenticated end-to-end encryption for the chat channel.
+//!
+//! Each client has a static X25519 identity keypair persisted to disk. After
+//! the punch loop opens a path to the peer, both sides run a small
+//! ephemeral-key handshake (tagged `0x10`, distinct from the `0x00`
+//! rendezvous frames and the `"punch"`/chat traffic) and bind the resulting
+//! shared secret to the peer's expected static key, so the session is both
+//! encrypted and mutually authenticated.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use tokio::time::{Instant, timeout};
+use x25519_dalek::{EphemeralSecret, PublicKey as XPublicKey, StaticSecret};
+use xsalsa20poly1305::aead::{Aead, AeadCore, generic_array::GenericArray};
+use xsalsa20poly1305::{KeyInit, XNonce, XSalsa20Poly1305};
+
+/// Frame tag for the handshake's ephemeral public key exchange.
+pub const HANDSHAKE_TAG: u8 = 0x10;
+
+/// Plaintext confirmed, under the derived key, once both sides agree on it.
+const CONFIRM_MESSAGE: &[u8] = b"confirm";
+
+const HANDSHAKE_ATTEMPTS: u32 = 8;
+const HANDSHAKE_RETRY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Resend `payload` up to `attempts` times, waiting up to `per_attempt`
+/// for a reply each time, until `validate` accepts one. Frames that don't
+/// validate (stale retransmits, mid-stream noise) are discarded without
+/// giving up, so a single dropped datagram on the freshly-punched path
+/// doesn't hang the handshake.
+async fn send_until_valid<Fs, Fr, S, R, T>(
+    send_frame: &mut Fs,
+    recv_frame: &mut Fr,
+    payload: Vec<u8>,
+    attempts: u32,
+    per_attempt: Duration,
+    mut validate: impl FnMut(&[u8]) -> Option<T>,
+) -> Result<T>
+where
+    Fs: FnMut(Vec<u8>) -> S,
+    Fr: FnMut() -> R,
+    S: std::future::Future<Output = Result<()>>,
+    R: std::future::Future<Output = Result<Vec<u8>>>,
+{
+    for _ in 0..attempts {
+        send_frame(payload.clone()).await?;
+        let attempt_deadline = Instant::now() + per_attempt;
+
+        loop {
+            let remaining = attempt_deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            match timeout(remaining, recv_frame()).await {
+                Ok(Ok(frame)) => {
+                    if let Some(value) = validate(&frame) {
+                        return Ok(value);
+                    }
+                }
+                Ok(Err(err)) => return Err(err),
+                Err(_elapsed) => break,
+            }
+        }
+    }
+    bail!("handshake timed out after {attempts} attempts with no valid reply")
+}
+
+/// A client's long-lived X25519 identity, persisted to disk so the peer
+/// can pin it across restarts via `--peer-key`.
+pub struct Identity {
+    secret: StaticSecret,
+    public: XPublicKey,
+}
+
+impl Identity {
+    /// Load the identity from `path`, generating and saving a fresh one if
+    /// it doesn't exist yet.
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        if let Ok(bytes) = fs::read(path) {
+            let array: [u8; 32] = bytes
+                .as_slice()
+                .try_into()
+                .context("identity file is not 32 bytes")?;
+            let secret = StaticSecret::from(array);
+            let public = XPublicKey::from(&secret);
+            return Ok(Self { secret, public });
+        }
+
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = XPublicKey::from(&secret);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("create identity dir")?;
+        }
+        fs::write(path, secret.to_bytes()).context("write identity file")?;
+        Ok(Self { secret, public })
+    }
+
+    pub fn public(&self) -> XPublicKey {
+        self.public
+    }
+
+    /// Hex-encoded public key, safe to print and share out of band.
+    pub fn public_hex(&self) -> String {
+        hex::encode(self.public.as_bytes())
+    }
+}
+
+/// Derive the AEAD key from the handshake transcript rather than using the
+/// raw DH output directly (DH shared secrets aren't uniformly random).
+/// Ephemeral and static public keys are sorted before hashing so both
+/// sides of the handshake compute an identical transcript regardless of
+/// which one ran first.
+fn derive_key(
+    static_shared: &x25519_dalek::SharedSecret,
+    ephemeral_shared: &x25519_dalek::SharedSecret,
+    our_ephemeral: &XPublicKey,
+    their_ephemeral: &XPublicKey,
+    our_static: &XPublicKey,
+    their_static: &XPublicKey,
+) -> [u8; 32] {
+    let (ephemeral_lo, ephemeral_hi) = sorted_pair(our_ephemeral, their_ephemeral);
+    let (static_lo, static_hi) = sorted_pair(our_static, their_static);
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"udp-hole-punching-client handshake v1");
+    hasher.update(static_shared.as_bytes());
+    hasher.update(ephemeral_shared.as_bytes());
+    hasher.update(ephemeral_lo.as_bytes());
+    hasher.update(ephemeral_hi.as_bytes());
+    hasher.update(static_lo.as_bytes());
+    hasher.update(static_hi.as_bytes());
+    hasher.finalize().into()
+}
+
+fn sorted_pair<'a>(a: &'a XPublicKey, b: &'a XPublicKey) -> (&'a XPublicKey, &'a XPublicKey) {
+    if a.as_bytes() <= b.as_bytes() { (a, b) } else { (b, a) }
+}
+
+/// Parse a `--peer-key` argument (hex-encoded X25519 public key).
+pub fn parse_peer_key(s: &str) -> Result<XPublicKey> {
+    let bytes = hex::decode(s).context("peer key must be hex")?;
+    let array: [u8; 32] = bytes
+        .as_slice()
+        .try_into()
+        .context("peer key must decode to 32 bytes")?;
+    Ok(XPublicKey::from(array))
+}
+
+/// The symmetric channel established after a successful handshake.
+pub struct SealedChannel {
+    cipher: XSalsa20Poly1305,
+}
+
+impl SealedChannel {
+    /// Run the ephemeral-key handshake over `send_frame`/`recv_frame`
+    /// closures supplied by the caller (so it can share the chat socket's
+    /// send/recv loop), then bind the shared secret to the peer's expected
+    /// static key.
+    ///
+    /// `send_frame` must prefix the payload with `HANDSHAKE_TAG` on the
+    /// wire; `recv_frame` must strip it before returning here.
+    pub async fn handshake<Fs, Fr, S, R>(
+        identity: &Identity,
+        expected_peer_key: &XPublicKey,
+        mut send_frame: Fs,
+        mut recv_frame: Fr,
+    ) -> Result<Self>
+    where
+        Fs: FnMut(Vec<u8>) -> S,
+        Fr: FnMut() -> R,
+        S: std::future::Future<Output = Result<()>>,
+        R: std::future::Future<Output = Result<Vec<u8>>>,
+    {
+        let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+        let our_ephemeral_public = XPublicKey::from(&ephemeral);
+
+        let mut outgoing = Vec::with_capacity(64);
+        outgoing.extend_from_slice(our_ephemeral_public.as_bytes());
+        outgoing.extend_from_slice(identity.public().as_bytes());
+
+        let (their_ephemeral, their_static) = send_until_valid(
+            &mut send_frame,
+            &mut recv_frame,
+            outgoing,
+            HANDSHAKE_ATTEMPTS,
+            HANDSHAKE_RETRY_TIMEOUT,
+            |frame| {
+                if frame.len() != 64 {
+                    return None;
+                }
+                let their_ephemeral =
+                    XPublicKey::from(<[u8; 32]>::try_from(&frame[..32]).unwrap());
+                let their_static = XPublicKey::from(<[u8; 32]>::try_from(&frame[32..]).unwrap());
+                Some((their_ephemeral, their_static))
+            },
+        )
+        .await?;
+
+        if their_static.as_bytes() != expected_peer_key.as_bytes() {
+            bail!("peer's static key does not match --peer-key; refusing channel");
+        }
+
+        let static_shared = identity.secret.diffie_hellman(&their_static);
+        let ephemeral_shared = ephemeral.diffie_hellman(&their_ephemeral);
+
+        let key_material = derive_key(
+            &static_shared,
+            &ephemeral_shared,
+            &our_ephemeral_public,
+            &their_ephemeral,
+            &identity.public(),
+            &their_static,
+        );
+        let key = GenericArray::from_slice(&key_material);
+        let channel = Self {
+            cipher: XSalsa20Poly1305::new(key),
+        };
+
+        // Key confirmation: exchange a frame sealed under the key we just
+        // derived, so a mismatch (e.g. a KDF transcript ordering bug)
+        // surfaces here as an explicit error instead of producing a
+        // channel that silently fails to decrypt every chat frame.
+        let confirm_payload = channel.seal(CONFIRM_MESSAGE);
+        send_until_valid(
+            &mut send_frame,
+            &mut recv_frame,
+            confirm_payload,
+            HANDSHAKE_ATTEMPTS,
+            HANDSHAKE_RETRY_TIMEOUT,
+            |frame| {
+                (channel.open(frame).as_deref() == Some(CONFIRM_MESSAGE)).then_some(())
+            },
+        )
+        .await
+        .context("key confirmation failed; peer derived a different key")?;
+
+        Ok(channel)
+    }
+
+    /// Seal a chat line: a fresh random 24-byte nonce followed by the
+    /// XSalsa20-Poly1305 ciphertext.
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = XSalsa20Poly1305::generate_nonce(&mut OsRng);
+        let mut ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .expect("encryption does not fail");
+        let mut framed = nonce.to_vec();
+        framed.append(&mut ciphertext);
+        framed
+    }
+
+    /// Open a sealed frame, dropping it (returning `None`) if the Poly1305
+    /// tag does not verify.
+    pub fn open(&self, frame: &[u8]) -> Option<Vec<u8>> {
+        if frame.len() < 24 {
+            return None;
+        }
+        let nonce = XNonce::from_slice(&frame[..24]);
+        self.cipher.decrypt(nonce, &frame[24..]).ok()
+    }
+}
@@ -0,0 +1,114 @@
+//! LAN multicast peer discovery, used as a fast path before falling back to
+//! the public rendezvous server.
+//!
+//! Both peers join the same multicast group and repeatedly announce their
+//! `name` and local `SocketAddr`. A peer listening for a specific `--peer`
+//! name can punch directly to whatever local address it hears, skipping
+//! the HTTP rendezvous round trip entirely when both clients happen to be
+//! on the same LAN.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use socket2::{Domain, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::time::{Instant, timeout};
+
+const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 42, 99);
+const MULTICAST_PORT: u16 = 42420;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Figure out this host's LAN-routable address by opening a UDP socket
+/// "connected" to an arbitrary public address; no packet is actually sent,
+/// this only asks the OS routing table which local interface it would use.
+async fn local_lan_ip() -> Result<Ipv4Addr> {
+    let probe = UdpSocket::bind("0.0.0.0:0").await?;
+    probe.connect((Ipv4Addr::new(203, 0, 113, 1), 80)).await?;
+    match probe.local_addr()?.ip() {
+        IpAddr::V4(ip) => Ok(ip),
+        IpAddr::V6(_) => anyhow::bail!("no local IPv4 address available for LAN discovery"),
+    }
+}
+
+/// Bind the multicast listener with `SO_REUSEADDR`/`SO_REUSEPORT` set, so
+/// two local clients (the common way to test LAN discovery on one host)
+/// can both bind `MULTICAST_PORT` instead of the second one failing with
+/// `EADDRINUSE`.
+fn bind_multicast_socket() -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None).context("create multicast socket")?;
+    socket
+        .set_reuse_address(true)
+        .context("set SO_REUSEADDR")?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true).context("set SO_REUSEPORT")?;
+    socket.set_nonblocking(true).context("set nonblocking")?;
+    let addr: SocketAddr = (Ipv4Addr::UNSPECIFIED, MULTICAST_PORT).into();
+    socket.bind(&addr.into()).context("bind multicast socket")?;
+    UdpSocket::from_std(socket.into()).context("hand multicast socket to tokio")
+}
+
+/// Join the multicast group and announce `name`/`local_addr` while
+/// listening for `peer` to announce itself, for up to `discover_timeout`.
+/// Returns `Ok(None)` both on a clean timeout and on any discovery setup
+/// failure (no LAN IP, multicast join refused, etc.) — LAN discovery is a
+/// fast path, not a requirement, so any way it can't work degrades to the
+/// rendezvous fallback rather than aborting the client.
+pub async fn discover_peer(
+    name: &str,
+    peer: &str,
+    local_port: u16,
+    discover_timeout: Duration,
+) -> Result<Option<SocketAddr>> {
+    match try_discover_peer(name, peer, local_port, discover_timeout).await {
+        Ok(found) => Ok(found),
+        Err(err) => {
+            println!("LAN discovery unavailable ({err}), falling back to rendezvous");
+            Ok(None)
+        }
+    }
+}
+
+async fn try_discover_peer(
+    name: &str,
+    peer: &str,
+    local_port: u16,
+    discover_timeout: Duration,
+) -> Result<Option<SocketAddr>> {
+    let lan_ip = local_lan_ip().await?;
+    let local_addr = SocketAddr::new(IpAddr::V4(lan_ip), local_port);
+
+    let socket = bind_multicast_socket()?;
+    socket
+        .join_multicast_v4(MULTICAST_GROUP, Ipv4Addr::UNSPECIFIED)
+        .context("join multicast group")?;
+    let group_addr = SocketAddr::new(IpAddr::V4(MULTICAST_GROUP), MULTICAST_PORT);
+
+    let announcement = format!("{name}|{local_addr}");
+    let deadline = Instant::now() + discover_timeout;
+    let mut buf = vec![0u8; 256];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+
+        socket.send_to(announcement.as_bytes(), group_addr).await?;
+
+        match timeout(remaining.min(ANNOUNCE_INTERVAL), socket.recv_from(&mut buf)).await {
+            Ok(Ok((len, _))) => {
+                let text = String::from_utf8_lossy(&buf[..len]);
+                if let Some((announced_name, addr)) = text.split_once('|') {
+                    if announced_name == peer {
+                        if let Ok(addr) = addr.trim().parse::<SocketAddr>() {
+                            return Ok(Some(addr));
+                        }
+                    }
+                }
+            }
+            Ok(Err(err)) => return Err(err.into()),
+            Err(_elapsed) => continue,
+        }
+    }
+}
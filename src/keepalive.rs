@@ -0,0 +1,60 @@
+//! Keepalive and peer-liveness supervision.
+//!
+//! The punch loop only opens the NAT mapping for as long as the burst
+//! lasts; most NATs expire an idle mapping well before a long-running chat
+//! session ends. This module sends a small heartbeat on a fixed interval
+//! and tracks the last time anything was heard from the peer, so the
+//! caller can detect a dead mapping and re-punch instead of the chat going
+//! silently stale.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+/// Frame tag for a keepalive heartbeat.
+pub const HEARTBEAT_TAG: u8 = 0x30;
+
+/// Shared last-seen timestamp, updated by the receive loop and read by the
+/// liveness supervisor.
+pub struct LivenessTracker {
+    last_seen: Mutex<Instant>,
+}
+
+impl LivenessTracker {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            last_seen: Mutex::new(Instant::now()),
+        })
+    }
+
+    pub async fn mark_seen(&self) {
+        *self.last_seen.lock().await = Instant::now();
+    }
+
+    async fn idle_for(&self) -> Duration {
+        self.last_seen.lock().await.elapsed()
+    }
+}
+
+/// Sends a heartbeat every `keepalive` interval until the peer has been
+/// silent for at least `liveness_timeout` (checked once per heartbeat), then
+/// returns `Ok(())` so the caller can re-punch and restart this supervisor.
+pub async fn supervise(
+    socket: &tokio::net::UdpSocket,
+    peer: std::net::SocketAddr,
+    tracker: &LivenessTracker,
+    keepalive: Duration,
+    liveness_timeout: Duration,
+) -> anyhow::Result<()> {
+    let mut ticker = tokio::time::interval(keepalive);
+    loop {
+        ticker.tick().await;
+        let _ = socket.send_to(&[HEARTBEAT_TAG], peer).await;
+
+        if tracker.idle_for().await >= liveness_timeout {
+            println!("peer {peer} appears dead (no traffic for {liveness_timeout:?})");
+            return Ok(());
+        }
+    }
+}
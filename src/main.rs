@@ -1,14 +1,32 @@
+mod crypto;
+mod discover;
+mod keepalive;
+mod nat;
+mod protocol;
+mod punch;
+mod reliability;
+
+use std::collections::{HashMap, VecDeque};
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result, bail};
+use bytes::Bytes;
 use clap::Parser;
 use reqwest::Client;
 use tokio::net::UdpSocket;
 use tokio::signal::ctrl_c;
+use tokio::sync::Mutex;
 use tokio::sync::mpsc::unbounded_channel;
 
+use crypto::{HANDSHAKE_TAG, Identity, SealedChannel};
+use keepalive::{HEARTBEAT_TAG, LivenessTracker};
+use nat::{NatType, probe_nat};
+use protocol::{FILE_CHUNK_SIZE, FILE_WINDOW, IncomingFile, Message};
+use reliability::{ACK_TAG, DATA_TAG, ReliableReceiver, ReliableSender};
+
 #[derive(Parser)]
 struct Cli {
     #[arg(long, default_value = "one")]
@@ -19,6 +37,59 @@ struct Cli {
     /// Rendezvous server IP (should be a public IP)
     #[arg(long, default_value = "45.151.30.139")]
     rendezvous: Ipv4Addr,
+    /// Path to this client's persisted X25519 identity keypair
+    #[arg(long, default_value = "identity.key")]
+    identity_path: PathBuf,
+    /// Expected peer X25519 public key (hex), required to open an
+    /// encrypted channel; the handshake is rejected if the peer presents
+    /// a different static key
+    #[arg(long)]
+    peer_key: String,
+    /// Seconds between keepalive heartbeats sent to the peer
+    #[arg(long, default_value_t = 15)]
+    keepalive_secs: u64,
+    /// Seconds of silence from the peer before it's considered dead and
+    /// re-punching is attempted (default is 3 missed heartbeats)
+    #[arg(long, default_value_t = 45)]
+    liveness_timeout_secs: u64,
+    /// How long to listen for the peer on the LAN multicast discovery
+    /// group before falling back to the rendezvous server
+    #[arg(long, default_value_t = 1500)]
+    discover_timeout_ms: u64,
+    /// Width of the candidate peer-port window probed on each side of the
+    /// last observed port, when predictive punching a symmetric NAT
+    #[arg(long, default_value_t = 16)]
+    punch_spread: u16,
+    /// Number of parallel local sockets to spray predictive punches from
+    /// when the peer is behind a symmetric NAT
+    #[arg(long, default_value_t = 4)]
+    punch_sockets: usize,
+}
+
+/// A line of user input, parsed into either a chat message or a
+/// `/send-file <path>` command.
+enum OutgoingCommand {
+    Chat(String),
+    SendFile(PathBuf),
+}
+
+impl OutgoingCommand {
+    fn parse(line: &str) -> Self {
+        match line.strip_prefix("/send-file ") {
+            Some(path) => OutgoingCommand::SendFile(PathBuf::from(path.trim())),
+            None => OutgoingCommand::Chat(line.to_string()),
+        }
+    }
+}
+
+/// Fire the punch burst: 100 `"punch"` packets at 25ms spacing, enough to
+/// open the NAT mapping for `peer`.
+async fn punch_burst(socket: &UdpSocket, peer: SocketAddr) -> Result<()> {
+    for _ in 0..100 {
+        socket.send_to(b"punch", peer).await?;
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    Ok(())
 }
 
 async fn register(rendezvous: &Ipv4Addr, name: &str) -> Result<UdpSocket> {
@@ -94,11 +165,220 @@ pub async fn get_peer_address(rendezvous: &Ipv4Addr, peer: &str) -> Result<Socke
     bail!("failed to resolve peer after {max_retries} attempts");
 }
 
+/// Send a single `HANDSHAKE_TAG`-prefixed frame to `peer` on `sock`.
+async fn send_handshake_frame(sock: &UdpSocket, peer: SocketAddr, payload: Vec<u8>) -> Result<()> {
+    let mut framed = Vec::with_capacity(payload.len() + 1);
+    framed.push(HANDSHAKE_TAG);
+    framed.extend_from_slice(&payload);
+    sock.send_to(&framed, peer).await?;
+    Ok(())
+}
+
+/// Wait for a `HANDSHAKE_TAG`-prefixed frame from `peer`, ignoring any
+/// leftover `"punch"` packets that arrive in the meantime.
+async fn recv_handshake_frame(sock: &UdpSocket, peer: SocketAddr) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; 256];
+    loop {
+        let (len, from) = sock.recv_from(&mut buf).await?;
+        if from != peer || len == 0 || buf[0] != HANDSHAKE_TAG {
+            continue;
+        }
+        return Ok(buf[1..len].to_vec());
+    }
+}
+
+/// Seal `message`, hand it to `sender` for reliable delivery, and send the
+/// resulting frame. Returns the reliability-layer sequence number assigned
+/// to the frame.
+async fn send_message(
+    sock: &UdpSocket,
+    channel: &SealedChannel,
+    sender: &mut ReliableSender,
+    dest: SocketAddr,
+    message: &Message,
+) -> u32 {
+    let sealed = channel.seal(&protocol::encode(message).expect("encode message"));
+    let (seq, frame) = sender.send(Bytes::from(sealed));
+    let _ = sock.send_to(&frame, dest).await;
+    seq
+}
+
+/// Verify `complete` against `file`'s advertised checksum and, if it
+/// matches, write it to disk under `file.name` (already reduced to a
+/// basename by the `FileOffer` handler, never the peer-supplied path).
+fn save_incoming_file(from: SocketAddr, file: &IncomingFile, complete: Vec<u8>) {
+    let digest = protocol::sha256_hex(&complete);
+    if digest != file.expected_sha256 {
+        println!("[{from}] file '{}' failed sha256 verification", file.name);
+        return;
+    }
+    let dest_path = PathBuf::from(format!("received_{}", file.name));
+    match std::fs::write(&dest_path, &complete) {
+        Ok(()) => println!(
+            "[{from}] file '{}' saved to {} (sha256 verified)",
+            file.name,
+            dest_path.display()
+        ),
+        Err(err) => println!("[{from}] failed to save file: {err}"),
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
-    let sock_pointer = Arc::new(register(&cli.rendezvous, &cli.name).await?);
-    let peer = get_peer_address(&cli.rendezvous, &cli.peer).await?;
+    let identity = Identity::load_or_create(&cli.identity_path)?;
+    println!("identity public key: {}", identity.public_hex());
+    let peer_key = crypto::parse_peer_key(&cli.peer_key)?;
+
+    let mut sock_pointer = Arc::new(register(&cli.rendezvous, &cli.name).await?);
+
+    // Best-effort: older rendezvous deployments don't answer the 0x01 echo
+    // tag at all, so a probing failure must not take down the client —
+    // fall back to assuming a cone NAT and let the plain punch loop run.
+    let nat_type = match probe_nat(&sock_pointer, &cli.rendezvous).await {
+        Ok((reflexive_addr, nat_type)) => {
+            println!("reflexive address: {reflexive_addr}");
+            match nat_type {
+                NatType::Cone => println!("NAT type: cone (endpoint-independent mapping)"),
+                NatType::Symmetric => {
+                    println!("NAT type: symmetric (port-dependent mapping); a single punch may fail")
+                }
+            }
+            nat_type
+        }
+        Err(err) => {
+            println!("NAT probe failed ({err}), assuming cone NAT");
+            NatType::Cone
+        }
+    };
+
+    let local_port = sock_pointer.local_addr()?.port();
+
+    // Race LAN discovery against the rendezvous lookup instead of gating on
+    // it: on a plain internet path (the common case) there's no LAN peer to
+    // find, so waiting out the full `discover_timeout_ms` before even
+    // asking the rendezvous server would needlessly stall every startup.
+    let discover_fut = discover::discover_peer(
+        &cli.name,
+        &cli.peer,
+        local_port,
+        Duration::from_millis(cli.discover_timeout_ms),
+    );
+    let rendezvous_fut = get_peer_address(&cli.rendezvous, &cli.peer);
+    tokio::pin!(discover_fut);
+    tokio::pin!(rendezvous_fut);
+
+    let mut discover_done = false;
+    let mut rendezvous_result: Option<SocketAddr> = None;
+    let (discovered, rendezvous_peer) = loop {
+        tokio::select! {
+            res = &mut discover_fut, if !discover_done => {
+                discover_done = true;
+                if let Some(addr) = res? {
+                    println!("found {} on the LAN at {addr}, skipping rendezvous", cli.peer);
+                    break (Some(addr), addr);
+                }
+                if let Some(addr) = rendezvous_result {
+                    break (None, addr);
+                }
+            }
+            res = &mut rendezvous_fut, if rendezvous_result.is_none() => {
+                let addr = res?;
+                rendezvous_result = Some(addr);
+                if discover_done {
+                    break (None, addr);
+                }
+            }
+        }
+    };
+
+    let peer = if discovered.is_none() && nat_type == NatType::Symmetric {
+        println!("symmetric NAT detected, attempting predictive port punching");
+        let port_history = punch::get_peer_port_history(&cli.rendezvous, &cli.peer).await?;
+        let winner = punch::predictive_punch(
+            match rendezvous_peer.ip() {
+                std::net::IpAddr::V4(ip) => ip,
+                std::net::IpAddr::V6(_) => bail!("predictive punching only supports IPv4 peers"),
+            },
+            rendezvous_peer.port(),
+            &port_history,
+            cli.punch_spread,
+            cli.punch_sockets,
+        )
+        .await?;
+
+        match winner {
+            Some((socket, peer_addr)) => {
+                println!("predictive punch succeeded, promoting winning socket to chat socket");
+                sock_pointer = socket;
+                peer_addr
+            }
+            None => {
+                println!("predictive punch found no reply, falling back to single-socket punch");
+                punch_burst(&sock_pointer, rendezvous_peer).await?;
+                rendezvous_peer
+            }
+        }
+    } else {
+        punch_burst(&sock_pointer, rendezvous_peer).await?;
+        rendezvous_peer
+    };
+
+    println!("punching done, starting handshake with {peer}");
+    let channel = Arc::new(
+        SealedChannel::handshake(
+            &identity,
+            &peer_key,
+            |payload| send_handshake_frame(&sock_pointer, peer, payload),
+            || recv_handshake_frame(&sock_pointer, peer),
+        )
+        .await?,
+    );
+    println!("handshake complete, channel authenticated and encrypted");
+
+    let peer = Arc::new(Mutex::new(peer));
+    let tracker = LivenessTracker::new();
+    let keepalive_interval = Duration::from_secs(cli.keepalive_secs);
+    let liveness_timeout = Duration::from_secs(cli.liveness_timeout_secs);
+
+    let supervisor_sock = Arc::clone(&sock_pointer);
+    let supervisor_peer = Arc::clone(&peer);
+    let supervisor_tracker = Arc::clone(&tracker);
+    let rendezvous = cli.rendezvous;
+    let peer_name = cli.peer.clone();
+    tokio::spawn(async move {
+        loop {
+            let current = *supervisor_peer.lock().await;
+            if let Err(err) = keepalive::supervise(
+                &supervisor_sock,
+                current,
+                &supervisor_tracker,
+                keepalive_interval,
+                liveness_timeout,
+            )
+            .await
+            {
+                println!("keepalive supervisor error: {err}");
+                return;
+            }
+
+            println!("re-resolving {peer_name} and re-punching...");
+            let fresh_peer = match get_peer_address(&rendezvous, &peer_name).await {
+                Ok(addr) => addr,
+                Err(err) => {
+                    println!("failed to re-resolve peer: {err}");
+                    continue;
+                }
+            };
+            if punch_burst(&supervisor_sock, fresh_peer).await.is_err() {
+                println!("re-punch to {fresh_peer} failed");
+                continue;
+            }
+            *supervisor_peer.lock().await = fresh_peer;
+            supervisor_tracker.mark_seen().await;
+            println!("reconnected to {fresh_peer}");
+        }
+    });
 
     let (tx, mut rx) = unbounded_channel();
     let tx_input = tx.clone();
@@ -111,7 +391,7 @@ async fn main() -> anyhow::Result<()> {
                 if msg.is_empty() {
                     continue;
                 }
-                if tx_input.send(msg).is_err() {
+                if tx_input.send(OutgoingCommand::parse(&msg)).is_err() {
                     break;
                 }
             } else {
@@ -120,35 +400,168 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    println!("chat with {} — type and press Enter", peer);
-    let mut buf = vec![0u8; 2048];
+    println!(
+        "chat with {} — type and press Enter, or /send-file <path>",
+        *peer.lock().await
+    );
+    let mut buf = vec![0u8; FILE_CHUNK_SIZE + 256];
 
     let chat_sock = Arc::clone(&sock_pointer);
+    let chat_channel = Arc::clone(&channel);
+    let chat_peer = Arc::clone(&peer);
+    let chat_tracker = Arc::clone(&tracker);
     tokio::spawn(async move {
+        let mut sender = ReliableSender::new();
+        let mut receiver = ReliableReceiver::new();
+        let mut retransmit_tick = tokio::time::interval(Duration::from_millis(100));
+        let mut file_tick = tokio::time::interval(Duration::from_millis(20));
+
+        let mut next_file_id: u64 = 0;
+        let mut outgoing_files: HashMap<u64, VecDeque<(u32, Vec<u8>)>> = HashMap::new();
+        let mut file_inflight: HashMap<u64, usize> = HashMap::new();
+        let mut seq_to_file: HashMap<u32, u64> = HashMap::new();
+        let mut incoming_files: HashMap<u64, IncomingFile> = HashMap::new();
+
         loop {
             tokio::select! {
                 res = chat_sock.recv_from(&mut buf) => {
-                    if let Ok((len, from)) = res {
-                        if len == 0 { continue; }
-                        let text = String::from_utf8_lossy(&buf[..len]);
-                        println!("[{from}] {text}");
+                    let Ok((len, from)) = res else { continue };
+                    if len == 0 || from != *chat_peer.lock().await { continue; }
+                    chat_tracker.mark_seen().await;
+                    match buf[0] {
+                        DATA_TAG => {
+                            let Some((seq, sealed)) = reliability::decode_data(&buf[1..len]) else { continue };
+                            let _ = chat_sock.send_to(&reliability::encode_ack(seq), from).await;
+                            for sealed in receiver.receive(seq, sealed) {
+                                let Some(plaintext) = chat_channel.open(&sealed) else {
+                                    println!("[{from}] dropped frame (bad tag)");
+                                    continue;
+                                };
+                                let Ok(message) = protocol::decode(&plaintext) else {
+                                    println!("[{from}] dropped frame (bad message)");
+                                    continue;
+                                };
+                                match message {
+                                    Message::Chat(text) => println!("[{from}] {text}"),
+                                    Message::FileOffer { id, name, len, sha256, chunk_count } => {
+                                        // The peer controls `name`; reduce it to a basename so
+                                        // a crafted "../../etc/passwd"-style offer can't escape
+                                        // the working directory we write into.
+                                        let name = Path::new(&name)
+                                            .file_name()
+                                            .map(|n| n.to_string_lossy().to_string())
+                                            .filter(|n| !n.is_empty())
+                                            .unwrap_or_else(|| "unnamed_file".to_string());
+                                        println!("[{from}] receiving file '{name}' ({len} bytes)");
+                                        let file = IncomingFile::new(name, len, sha256, chunk_count);
+                                        if file.is_empty() {
+                                            // No FileChunk is ever sent for an empty file, so
+                                            // receive_chunk would never fire; finish here instead.
+                                            save_incoming_file(from, &file, Vec::new());
+                                        } else {
+                                            incoming_files.insert(id, file);
+                                        }
+                                    }
+                                    Message::FileChunk { id, seq, bytes } => {
+                                        if let Some(file) = incoming_files.get_mut(&id) {
+                                            if let Some(complete) = file.receive_chunk(seq, bytes) {
+                                                save_incoming_file(from, file, complete);
+                                                incoming_files.remove(&id);
+                                            }
+                                        }
+                                    }
+                                    Message::Ping => {
+                                        let dest = *chat_peer.lock().await;
+                                        send_message(&chat_sock, &chat_channel, &mut sender, dest, &Message::Pong).await;
+                                    }
+                                    Message::Pong => println!("[{from}] pong"),
+                                }
+                            }
+                        }
+                        ACK_TAG => {
+                            if let Some(seq) = reliability::decode_ack(&buf[1..len]) {
+                                sender.handle_ack(seq);
+                                if let Some(id) = seq_to_file.remove(&seq) {
+                                    if let Some(count) = file_inflight.get_mut(&id) {
+                                        *count = count.saturating_sub(1);
+                                        // Once every chunk has been sent (no queue left in
+                                        // outgoing_files) and acked (count back to 0), the
+                                        // transfer is done; otherwise file_inflight would
+                                        // grow by one stale entry per file sent.
+                                        if *count == 0 && !outgoing_files.contains_key(&id) {
+                                            file_inflight.remove(&id);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        HANDSHAKE_TAG | HEARTBEAT_TAG => continue,
+                        _ => continue,
+                    }
+                }
+                Some(command) = rx.recv() => {
+                    let dest = *chat_peer.lock().await;
+                    match command {
+                        OutgoingCommand::Chat(line) => {
+                            send_message(&chat_sock, &chat_channel, &mut sender, dest, &Message::Chat(line.clone())).await;
+                            println!("Sent: {line}");
+                        }
+                        OutgoingCommand::SendFile(path) => {
+                            match std::fs::read(&path) {
+                                Ok(data) => {
+                                    let id = next_file_id;
+                                    next_file_id += 1;
+                                    let name = path.file_name().map(|n| n.to_string_lossy().to_string())
+                                        .unwrap_or_else(|| path.display().to_string());
+                                    let sha256 = protocol::sha256_hex(&data);
+                                    let len = data.len() as u64;
+
+                                    let chunks: VecDeque<(u32, Vec<u8>)> = data
+                                        .chunks(FILE_CHUNK_SIZE)
+                                        .enumerate()
+                                        .map(|(seq, bytes)| (seq as u32, bytes.to_vec()))
+                                        .collect();
+                                    let chunk_count = chunks.len() as u32;
+
+                                    send_message(&chat_sock, &chat_channel, &mut sender, dest, &Message::FileOffer {
+                                        id, name: name.clone(), len, sha256, chunk_count,
+                                    }).await;
+
+                                    println!("offering '{name}' ({len} bytes, {chunk_count} chunks)");
+                                    outgoing_files.insert(id, chunks);
+                                    file_inflight.insert(id, 0);
+                                }
+                                Err(err) => println!("could not read {}: {err}", path.display()),
+                            }
+                        }
+                    }
+                }
+                _ = retransmit_tick.tick() => {
+                    let dest = *chat_peer.lock().await;
+                    for frame in sender.due_retransmits() {
+                        let _ = chat_sock.send_to(&frame, dest).await;
                     }
                 }
-                Some(line) = rx.recv() => {
-                    if chat_sock.send_to(line.as_bytes(), peer).await.is_ok() {
-                        println!("Sent: {line}");
+                _ = file_tick.tick() => {
+                    let dest = *chat_peer.lock().await;
+                    for (&id, queue) in outgoing_files.iter_mut() {
+                        let inflight = file_inflight.entry(id).or_insert(0);
+                        while *inflight < FILE_WINDOW {
+                            let Some((seq, bytes)) = queue.pop_front() else { break };
+                            let message = Message::FileChunk { id, seq, bytes };
+                            let sealed = chat_channel.seal(&protocol::encode(&message).expect("encode message"));
+                            let (reliability_seq, frame) = sender.send(Bytes::from(sealed));
+                            let _ = chat_sock.send_to(&frame, dest).await;
+                            seq_to_file.insert(reliability_seq, id);
+                            *inflight += 1;
+                        }
                     }
+                    outgoing_files.retain(|_, queue| !queue.is_empty());
                 }
             }
         }
     });
 
-    let punch_sock = Arc::clone(&sock_pointer);
-    for _ in 0..100 {
-        punch_sock.send_to(b"punch", peer).await?;
-        tokio::time::sleep(Duration::from_millis(25)).await;
-    }
-
     println!("Client running. Press Ctrl+C to exit.");
     ctrl_c().await?;
     println!("Shutting down...");
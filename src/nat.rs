@@ -0,0 +1,72 @@
+//! Reflexive-address discovery and NAT-type detection.
+//!
+//! Before punching, the client asks the rendezvous server to echo back the
+//! source address it observed (tag `0x01`), the same trick used by
+//! `ip_echo_server`-style services. Sending that echo request from one
+//! socket to two distinct server ports and comparing the external port the server
+//! reports back tells us whether the local NAT hands out a consistent
+//! mapping per internal socket (cone) or a fresh one per destination
+//! (symmetric), which determines whether a single punch can work at all.
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+/// Rendezvous request tag for the reflexive-address echo.
+pub const ECHO_REQ_TAG: u8 = 0x01;
+
+const ECHO_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The second rendezvous port used purely to probe NAT behavior. The
+/// primary registration/punch traffic still goes to port 4200.
+const ECHO_PROBE_PORT_B: u16 = 4201;
+const ECHO_PRIMARY_PORT: u16 = 4200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatType {
+    /// Same external port reported for both probe destinations: a single
+    /// punch to a known peer port should succeed.
+    Cone,
+    /// Different external ports per destination: plain punching to a
+    /// previously observed peer port will likely fail.
+    Symmetric,
+}
+
+/// Ask the rendezvous server (on `port`) what external address it saw for
+/// `socket`, using the same socket we intend to punch with so the mapping
+/// we learn is the one that will actually be used.
+async fn echo(socket: &UdpSocket, rendezvous: &Ipv4Addr, port: u16) -> Result<SocketAddr> {
+    let addr = SocketAddr::V4(SocketAddrV4::new(*rendezvous, port));
+    socket.send_to(&[ECHO_REQ_TAG], addr).await?;
+
+    let mut buf = [0u8; 32];
+    let (len, from) = timeout(ECHO_TIMEOUT, socket.recv_from(&mut buf))
+        .await
+        .context("timed out waiting for echo reply")??;
+    if from != addr {
+        bail!("echo reply from unexpected address {from}");
+    }
+
+    let body = std::str::from_utf8(&buf[..len]).context("echo reply is not utf8")?;
+    body.trim()
+        .parse()
+        .with_context(|| format!("parse echo reply address from '{}'", body.trim()))
+}
+
+/// Probe the NAT in front of `socket` by echoing off two distinct
+/// rendezvous ports and comparing the external port each one reports.
+pub async fn probe_nat(socket: &UdpSocket, rendezvous: &Ipv4Addr) -> Result<(SocketAddr, NatType)> {
+    let first = echo(socket, rendezvous, ECHO_PRIMARY_PORT).await?;
+    let second = echo(socket, rendezvous, ECHO_PROBE_PORT_B).await?;
+
+    let nat_type = if first.port() == second.port() {
+        NatType::Cone
+    } else {
+        NatType::Symmetric
+    };
+
+    Ok((first, nat_type))
+}
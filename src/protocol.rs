@@ -0,0 +1,103 @@
+//! Typed request/response layer on top of the sealed, reliable datagram
+//! channel: a `Message` enum serialized with `rmp-serde` (msgpack) replaces
+//! the raw chat string, so structured operations like file transfer can
+//! share the same punched socket as plain chat.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Chunk size used when streaming a file; small enough that a sealed,
+/// framed chunk fits in a single UDP datagram.
+pub const FILE_CHUNK_SIZE: usize = 1024;
+
+/// Maximum number of FileChunk messages allowed in flight (unacked) at
+/// once, so a large transfer can't flood the retransmit buffer.
+pub const FILE_WINDOW: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Chat(String),
+    FileOffer {
+        id: u64,
+        name: String,
+        len: u64,
+        sha256: String,
+        chunk_count: u32,
+    },
+    FileChunk {
+        id: u64,
+        seq: u32,
+        bytes: Vec<u8>,
+    },
+    Ping,
+    Pong,
+}
+
+/// Serialize `message` to msgpack.
+pub fn encode(message: &Message) -> anyhow::Result<Vec<u8>> {
+    Ok(rmp_serde::to_vec(message)?)
+}
+
+/// Deserialize a msgpack-encoded `Message`.
+pub fn decode(bytes: &[u8]) -> anyhow::Result<Message> {
+    Ok(rmp_serde::from_slice(bytes)?)
+}
+
+/// Hex-encoded SHA-256 of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hex::encode(hasher.finalize())
+}
+
+/// Tracks the receive side of one in-flight file transfer.
+pub struct IncomingFile {
+    pub name: String,
+    pub expected_len: u64,
+    pub expected_sha256: String,
+    pub expected_chunks: u32,
+    pub chunks: std::collections::BTreeMap<u32, Vec<u8>>,
+}
+
+impl IncomingFile {
+    pub fn new(
+        name: String,
+        expected_len: u64,
+        expected_sha256: String,
+        expected_chunks: u32,
+    ) -> Self {
+        Self {
+            name,
+            expected_len,
+            expected_sha256,
+            expected_chunks,
+            chunks: std::collections::BTreeMap::new(),
+        }
+    }
+
+    /// `true` for a zero-chunk (empty) offer, which completes immediately
+    /// with no `FileChunk` ever arriving to trigger `receive_chunk`.
+    pub fn is_empty(&self) -> bool {
+        self.expected_chunks == 0
+    }
+
+    /// Record a chunk; returns the reassembled bytes once exactly
+    /// `expected_chunks` chunks have arrived and they form a contiguous
+    /// `0..expected_chunks` run. Relying on `received_len` alone would
+    /// accept a transfer missing an interior chunk so long as a later
+    /// duplicate padded the byte count back up; checking chunk count and
+    /// the first/last key rules that out, since chunk keys are unique.
+    pub fn receive_chunk(&mut self, seq: u32, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.chunks.insert(seq, bytes);
+
+        if self.chunks.len() != self.expected_chunks as usize {
+            return None;
+        }
+        let first_contiguous = self.chunks.keys().next() == Some(&0);
+        let last_contiguous = self.chunks.keys().next_back() == Some(&(self.expected_chunks - 1));
+        if !first_contiguous || !last_contiguous {
+            return None;
+        }
+        Some(self.chunks.values().flatten().copied().collect())
+    }
+}
@@ -0,0 +1,179 @@
+//! Port-prediction punching for symmetric (port-dependent) NATs.
+//!
+//! A plain punch to the peer's last observed port works for cone NATs but
+//! almost never for symmetric ones, since the NAT allocates a fresh
+//! external port per destination. Instead we estimate the NAT's port
+//! allocation delta from the peer's recent port history (reported by the
+//! rendezvous server), open several local sockets, and spray punches
+//! across a window of candidate peer ports from each one — a birthday-
+//! paradox approach to hitting the right (local socket, peer port) pair.
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+/// Ask the rendezvous server for `peer`'s recent sequence of mapped
+/// external ports, most recent last. Returns an empty list if the server
+/// doesn't support port history (older rendezvous deployments), in which
+/// case callers fall back to a default +1 delta guess.
+pub async fn get_peer_port_history(rendezvous: &Ipv4Addr, peer: &str) -> Result<Vec<u16>> {
+    let client = Client::new();
+    let url = format!("http://{rendezvous}:8080/api/ports/{peer}");
+
+    let response = match client.get(&url).send().await {
+        Ok(r) => r,
+        Err(_) => return Ok(Vec::new()),
+    };
+    if response.status().as_u16() != 200 {
+        return Ok(Vec::new());
+    }
+
+    let body = response.text().await.context("read port history body")?;
+    Ok(body
+        .split(',')
+        .filter_map(|p| p.trim().parse::<u16>().ok())
+        .collect())
+}
+
+/// Estimate the NAT's per-destination port allocation delta from a
+/// sequence of observed external ports, averaging consecutive differences.
+/// Defaults to `1` (the most common symmetric NAT behavior) when there's
+/// not enough history to estimate from.
+fn estimate_delta(history: &[u16]) -> i32 {
+    if history.len() < 2 {
+        return 1;
+    }
+    let mut total = 0i64;
+    let mut count = 0i64;
+    for pair in history.windows(2) {
+        total += pair[1] as i64 - pair[0] as i64;
+        count += 1;
+    }
+    let avg = total / count.max(1);
+    if avg == 0 { 1 } else { avg as i32 }
+}
+
+/// Build the candidate peer-port window: `last_port ± spread`, plus
+/// `last_port + k*delta` for `k` in `1..64`.
+fn candidate_ports(last_port: u16, spread: u16, delta: i32) -> Vec<u16> {
+    let mut candidates = Vec::new();
+    let base = last_port as i32;
+
+    for offset in -(spread as i32)..=spread as i32 {
+        let candidate = base + offset;
+        if (1..=u16::MAX as i32).contains(&candidate) {
+            candidates.push(candidate as u16);
+        }
+    }
+    for k in 1..64 {
+        let candidate = base + k * delta;
+        if (1..=u16::MAX as i32).contains(&candidate) {
+            candidates.push(candidate as u16);
+        }
+    }
+    candidates.sort_unstable();
+    candidates.dedup();
+    candidates
+}
+
+/// Spray punches from `punch_sockets` local sockets across the predicted
+/// candidate window for `peer_ip`, and return whichever socket first
+/// receives a reply, promoted to be the chat socket. Returns `None` if
+/// nothing answers within 3 seconds.
+pub async fn predictive_punch(
+    peer_ip: Ipv4Addr,
+    last_port: u16,
+    port_history: &[u16],
+    punch_spread: u16,
+    punch_sockets: usize,
+) -> Result<Option<(Arc<UdpSocket>, SocketAddr)>> {
+    let delta = estimate_delta(port_history);
+    let candidates = candidate_ports(last_port, punch_spread, delta);
+
+    let mut sockets = Vec::with_capacity(punch_sockets);
+    for _ in 0..punch_sockets {
+        sockets.push(Arc::new(UdpSocket::bind("0.0.0.0:0").await?));
+    }
+
+    for socket in &sockets {
+        for &port in &candidates {
+            let addr = SocketAddr::V4(SocketAddrV4::new(peer_ip, port));
+            let _ = socket.send_to(b"punch", addr).await;
+        }
+    }
+
+    let mut buf = vec![0u8; 64];
+    let deadline = Duration::from_secs(3);
+    let winner = timeout(deadline, async {
+        loop {
+            for socket in &sockets {
+                if let Ok(Ok((len, from))) =
+                    timeout(Duration::from_millis(20), socket.recv_from(&mut buf)).await
+                {
+                    if len > 0 && from.ip() == std::net::IpAddr::V4(peer_ip) {
+                        return (Arc::clone(socket), from);
+                    }
+                }
+            }
+        }
+    })
+    .await;
+
+    Ok(winner.ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_delta_defaults_to_one_with_insufficient_history() {
+        assert_eq!(estimate_delta(&[]), 1);
+        assert_eq!(estimate_delta(&[100]), 1);
+    }
+
+    #[test]
+    fn estimate_delta_averages_consecutive_differences() {
+        assert_eq!(estimate_delta(&[100, 105, 110]), 5);
+        assert_eq!(estimate_delta(&[100, 90, 80]), -10);
+    }
+
+    #[test]
+    fn estimate_delta_falls_back_to_one_when_average_is_zero() {
+        assert_eq!(estimate_delta(&[100, 110, 100]), 1);
+    }
+
+    #[test]
+    fn candidate_ports_covers_the_spread_window() {
+        let candidates = candidate_ports(1000, 2, 1);
+        assert!(candidates.contains(&998));
+        assert!(candidates.contains(&999));
+        assert!(candidates.contains(&1000));
+        assert!(candidates.contains(&1001));
+        assert!(candidates.contains(&1002));
+    }
+
+    #[test]
+    fn candidate_ports_clamps_at_port_range_boundaries() {
+        // Near port 0: offsets below 1 must be dropped, not wrap negative.
+        let low = candidate_ports(1, 5, 1);
+        assert!(low.iter().all(|&p| p >= 1));
+
+        // Near u16::MAX: offsets above it must be dropped, not overflow.
+        let high = candidate_ports(u16::MAX, 5, 1);
+        assert!(high.iter().all(|&p| p <= u16::MAX));
+        assert!(high.contains(&u16::MAX));
+    }
+
+    #[test]
+    fn candidate_ports_dedupes_overlap_between_spread_and_delta() {
+        let candidates = candidate_ports(1000, 1, 1);
+        let unique: std::collections::BTreeSet<u16> = candidates.iter().copied().collect();
+        assert_eq!(candidates.len(), unique.len());
+    }
+}
@@ -0,0 +1,203 @@
+//! At-least-once, in-order delivery on top of the raw chat datagrams.
+//!
+//! Every outgoing chat payload is framed with a 1-byte type tag and a
+//! monotonically increasing 32-bit sequence number. Unacked `DATA` frames
+//! sit in a retransmit buffer and are resent after a timeout that starts at
+//! 200 ms and doubles on each resend, capped at 3 s; the timeout is not
+//! adjusted from measured round-trip times. Incoming `DATA` is ACKed
+//! immediately, buffered if out of order, and only handed to the caller
+//! once it's next in line.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+/// Frame tag for a reliable data payload.
+pub const DATA_TAG: u8 = 0x20;
+/// Frame tag for an acknowledgement of a data payload.
+pub const ACK_TAG: u8 = 0x21;
+
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(200);
+const MAX_TIMEOUT: Duration = Duration::from_secs(3);
+
+struct PendingFrame {
+    payload: Bytes,
+    sent_at: Instant,
+    timeout: Duration,
+}
+
+/// Tracks unacked outgoing frames and assigns sequence numbers.
+pub struct ReliableSender {
+    next_seq: u32,
+    pending: BTreeMap<u32, PendingFrame>,
+}
+
+impl ReliableSender {
+    pub fn new() -> Self {
+        Self {
+            next_seq: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Frame `payload` as a new DATA packet, remembering it for
+    /// retransmission, and return its sequence number plus the bytes to
+    /// send on the wire.
+    pub fn send(&mut self, payload: Bytes) -> (u32, Vec<u8>) {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        let frame = encode_data(seq, &payload);
+        self.pending.insert(
+            seq,
+            PendingFrame {
+                payload,
+                sent_at: Instant::now(),
+                timeout: INITIAL_TIMEOUT,
+            },
+        );
+        (seq, frame)
+    }
+
+    /// Remove a frame from the retransmit buffer once its ACK arrives.
+    pub fn handle_ack(&mut self, seq: u32) {
+        self.pending.remove(&seq);
+    }
+
+    /// Re-encode any frame that has been outstanding longer than its
+    /// current timeout, doubling that timeout (capped) for next time.
+    pub fn due_retransmits(&mut self) -> Vec<Vec<u8>> {
+        let now = Instant::now();
+        let mut frames = Vec::new();
+        for (&seq, pending) in self.pending.iter_mut() {
+            if now.duration_since(pending.sent_at) >= pending.timeout {
+                frames.push(encode_data(seq, &pending.payload));
+                pending.sent_at = now;
+                pending.timeout = (pending.timeout * 2).min(MAX_TIMEOUT);
+            }
+        }
+        frames
+    }
+}
+
+/// Reassembles incoming DATA frames into contiguous, in-order delivery.
+pub struct ReliableReceiver {
+    next_expected: u32,
+    reorder: BTreeMap<u32, Vec<u8>>,
+}
+
+impl ReliableReceiver {
+    pub fn new() -> Self {
+        Self {
+            next_expected: 0,
+            reorder: BTreeMap::new(),
+        }
+    }
+
+    /// Record a received DATA frame and return any payloads now ready for
+    /// in-order delivery (possibly more than one if it closed a gap).
+    /// Duplicates below the delivery watermark are silently dropped.
+    pub fn receive(&mut self, seq: u32, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        if seq_is_before(seq, self.next_expected) {
+            return Vec::new();
+        }
+        self.reorder.entry(seq).or_insert(payload);
+
+        let mut deliverable = Vec::new();
+        while let Some(payload) = self.reorder.remove(&self.next_expected) {
+            deliverable.push(payload);
+            self.next_expected = self.next_expected.wrapping_add(1);
+        }
+        deliverable
+    }
+}
+
+/// Wrapping-aware "is `a` strictly before the delivery watermark `b`".
+fn seq_is_before(a: u32, b: u32) -> bool {
+    a.wrapping_sub(b) > u32::MAX / 2
+}
+
+pub fn encode_data(seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(DATA_TAG);
+    frame.extend_from_slice(&seq.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+pub fn encode_ack(seq: u32) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(5);
+    frame.push(ACK_TAG);
+    frame.extend_from_slice(&seq.to_be_bytes());
+    frame
+}
+
+/// Decode a DATA frame's sequence number and payload (tag already stripped
+/// by the caller via the first byte check).
+pub fn decode_data(frame: &[u8]) -> Option<(u32, Vec<u8>)> {
+    if frame.len() < 5 {
+        return None;
+    }
+    let seq = u32::from_be_bytes(frame[..4].try_into().unwrap());
+    Some((seq, frame[4..].to_vec()))
+}
+
+/// Decode an ACK frame's sequence number.
+pub fn decode_ack(frame: &[u8]) -> Option<u32> {
+    if frame.len() < 4 {
+        return None;
+    }
+    Some(u32::from_be_bytes(frame[..4].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seq_is_before_handles_wraparound() {
+        assert!(!seq_is_before(5, 5));
+        assert!(!seq_is_before(6, 5));
+        assert!(seq_is_before(4, 5));
+        // Just past a u32 wrap, the new seq should still read as "after".
+        assert!(!seq_is_before(0, u32::MAX));
+        assert!(seq_is_before(u32::MAX, 0));
+    }
+
+    #[test]
+    fn receiver_delivers_in_order_despite_reordering() {
+        let mut receiver = ReliableReceiver::new();
+
+        assert!(receiver.receive(1, b"b".to_vec()).is_empty());
+        assert!(receiver.receive(2, b"c".to_vec()).is_empty());
+        let delivered = receiver.receive(0, b"a".to_vec());
+        assert_eq!(delivered, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+    }
+
+    #[test]
+    fn receiver_drops_duplicates_below_watermark() {
+        let mut receiver = ReliableReceiver::new();
+
+        assert_eq!(receiver.receive(0, b"a".to_vec()), vec![b"a".to_vec()]);
+        assert!(receiver.receive(0, b"a".to_vec()).is_empty());
+    }
+
+    #[test]
+    fn receiver_handles_sequence_wraparound() {
+        let mut receiver = ReliableReceiver {
+            next_expected: u32::MAX,
+            reorder: BTreeMap::new(),
+        };
+
+        assert_eq!(
+            receiver.receive(u32::MAX, b"last".to_vec()),
+            vec![b"last".to_vec()]
+        );
+        assert_eq!(
+            receiver.receive(0, b"first-after-wrap".to_vec()),
+            vec![b"first-after-wrap".to_vec()]
+        );
+        assert_eq!(receiver.next_expected, 1);
+    }
+}